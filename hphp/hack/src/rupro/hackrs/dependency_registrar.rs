@@ -0,0 +1,44 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the "hack" directory of this source tree.
+
+use pos::{ClassConstName, MethodName, PropName, TypeConstName, TypeName};
+
+/// The left-hand side of a dependency edge: the thing whose folded decl (or
+/// other derived fact) is being computed, and which should therefore be
+/// invalidated/recomputed when something it depends on changes.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum DeclName {
+    Type(TypeName),
+}
+
+/// The right-hand side of a dependency edge: the specific input that was
+/// read while computing the fact named by a `DeclName`.
+///
+/// Folding a class used to only ever record a dependency on a parent's
+/// constructor (`Constructor`), which meant any change to that parent
+/// invalidated every descendant's fold, regardless of which member (if any)
+/// the descendant actually inherited. The member-level variants let the
+/// folder record precisely which method, property, class constant, or type
+/// constant it read from a given parent, so incremental invalidation can be
+/// as fine-grained as the query/on-demand dependency graph demands.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum DependencyName {
+    Constructor(TypeName),
+    Method(TypeName, MethodName),
+    Prop(TypeName, PropName),
+    Const(TypeName, ClassConstName),
+    TypeConst(TypeName, TypeConstName),
+}
+
+/// Abstraction over wherever the incremental dependency graph actually
+/// lives, so that decl folding can record edges without needing to know
+/// how (or whether) they're persisted.
+pub trait DependencyRegistrar: std::fmt::Debug + Send + Sync {
+    fn add_dependency(
+        &self,
+        dependent: DeclName,
+        dependency: DependencyName,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+}
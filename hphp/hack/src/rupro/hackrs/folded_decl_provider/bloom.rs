@@ -0,0 +1,83 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the "hack" directory of this source tree.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Bits allotted per entry when a filter is sized with `with_capacity`.
+/// Ten bits/entry is the standard rule of thumb for keeping the false
+/// positive rate low (under ~1%) without the filter outgrowing the member
+/// maps it's meant to prefilter lookups against.
+const BITS_PER_ENTRY: usize = 10;
+
+/// A probabilistic "definitely absent" / "maybe present" prefilter for
+/// folded member lookups. Most lookups against a large class's member maps
+/// (props, methods, consts, ...) miss -- callers are often just probing
+/// whether an override exists -- and a `BloomFilter` lets those misses skip
+/// straight past the underlying `IndexMap` without hashing into it.
+///
+/// Built once, after folding, from the final member name sets; see
+/// `Inherited::member_filter`.
+#[derive(Clone, Debug)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: usize,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    /// Sizes the filter at `BITS_PER_ENTRY` bits per expected entry, and
+    /// picks the corresponding near-optimal number of hash functions
+    /// (`k = bits_per_entry * ln(2)`).
+    pub fn with_capacity(expected_entries: usize) -> Self {
+        let num_bits = (expected_entries * BITS_PER_ENTRY).max(64);
+        let num_words = (num_bits + 63) / 64;
+        let num_hashes = ((BITS_PER_ENTRY as f64) * std::f64::consts::LN_2).round() as u32;
+        Self {
+            bits: vec![0u64; num_words],
+            num_bits: num_words * 64,
+            num_hashes: num_hashes.max(1),
+        }
+    }
+
+    // Kirsch-Mitzenmacher: derive all `k` probe indices from a single pair
+    // of 64-bit hashes (`h_i = h1 + i * h2`) instead of computing `k`
+    // independent hash functions.
+    fn probe_indices(&self, value: &impl Hash) -> impl Iterator<Item = usize> + '_ {
+        let mut h1_hasher = DefaultHasher::new();
+        value.hash(&mut h1_hasher);
+        let h1 = h1_hasher.finish();
+
+        // Mix `h1` through a second round instead of re-hashing `value`, so
+        // a second, independent-enough hash doesn't require a second
+        // traversal of `value` itself.
+        let mut h2_hasher = DefaultHasher::new();
+        h1.hash(&mut h2_hasher);
+        let h2 = h2_hasher.finish();
+
+        (0..self.num_hashes)
+            .map(move |i| h1.wrapping_add((i as u64).wrapping_mul(h2)))
+            .map(move |h| (h % self.num_bits as u64) as usize)
+    }
+
+    pub fn insert(&mut self, value: &impl Hash) {
+        for idx in self.probe_indices(value).collect::<Vec<_>>() {
+            self.bits[idx / 64] |= 1 << (idx % 64);
+        }
+    }
+
+    /// `false` means `value` is definitely not a member; `true` means it
+    /// might be (false positives are possible, false negatives are not).
+    pub fn may_contain(&self, value: &impl Hash) -> bool {
+        self.probe_indices(value)
+            .all(|idx| self.bits[idx / 64] & (1 << (idx % 64)) != 0)
+    }
+}
+
+impl Default for BloomFilter {
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
+}
@@ -3,13 +3,18 @@
 // This source code is licensed under the MIT license found in the
 // LICENSE file in the "hack" directory of this source tree.
 
-use super::{subst::Substitution, Result};
+use super::{
+    bloom::BloomFilter, subst::ProjectionMode, subst::Substitution, subst::TyFolder,
+    subst::TypeFoldable, Result,
+};
 use crate::dependency_registrar::{DeclName, DependencyName, DependencyRegistrar};
 use indexmap::map::Entry;
 use pos::{
-    ClassConstNameIndexMap, MethodName, MethodNameIndexMap, Pos, PropNameIndexMap,
-    TypeConstNameIndexMap, TypeName, TypeNameIndexMap,
+    ClassConstName, ClassConstNameIndexMap, MethodName, MethodNameIndexMap, Pos, PropName,
+    PropNameIndexMap, TypeConstName, TypeConstNameIndexMap, TypeName, TypeNameIndexMap,
+    TypeNameSet,
 };
+use std::collections::HashSet;
 use std::sync::Arc;
 use ty::decl::{
     folded::Constructor, subst::Subst, ty::ConsistentKind, AbstractTypeconst, Abstraction,
@@ -20,6 +25,76 @@ use ty::reason::Reason;
 
 // note(sf, 2022-02-03): c.f. hphp/hack/src/decl/decl_inherit.ml
 
+/// Which kind of member a `DeclFoldError::MemberConflict` was found on.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MemberKind {
+    Method,
+    StaticMethod,
+    Prop,
+    StaticProp,
+}
+
+/// A problem noticed while folding a child's inherited members. Several of
+/// these used to be silently dropped, leaving HHVM's runtime to catch the
+/// resulting error (or not) at invocation time; `Inherited::make` now
+/// collects them so the type checker can surface them at decl-fold time.
+#[derive(Debug, Clone)]
+pub enum DeclFoldError {
+    /// Two sibling traits (or interfaces) each declare a non-abstract,
+    /// non-synthesized member with the same name, and neither is inherited
+    /// from the other along a single linear chain -- so the choice of which
+    /// one wins is arbitrary rather than principled.
+    MemberConflict {
+        name: String,
+        kind: MemberKind,
+        owner_a: TypeName,
+        owner_b: TypeName,
+        pos_a: Pos,
+        pos_b: Pos,
+    },
+    /// Two abstract type constants with incomparable constraint bounds were
+    /// inherited from distinct parents; `Typing_extends` warns about this
+    /// case because the choice of which bound to use depends on declaration
+    /// order, which is easy to get wrong by accident.
+    AmbiguousTypeConst {
+        name: TypeConstName,
+        owner_a: TypeName,
+        owner_b: TypeName,
+    },
+    /// `child` is its own ancestor: some parent reached while folding `child`
+    /// already counts `child` among *its* ancestors. `parents` is assumed to
+    /// contain fully-folded decls, so this can only happen if a cyclic
+    /// `extends`/`uses`/`require extends`/`implements` graph slipped past
+    /// whatever populated `parents` -- in which case trusting that parent's
+    /// members would fold `child` from data that (transitively) depends on
+    /// `child` itself.
+    ///
+    /// This only catches a cycle that closes back on `child` itself; it
+    /// can't detect one entirely among `child`'s ancestors that never
+    /// routes back through `child`. That's a narrower guarantee than a full
+    /// DFS-with-a-path-stack would give, but this layer never actually
+    /// recurses into folding a parent (`parents` holds decls that are
+    /// already fully folded by the time `child` is folded), so a cycle not
+    /// involving `child` would have to be caught by whatever builds
+    /// `parents` in the first place, not here. See `MemberFolder::visiting`
+    /// and `check_for_cycle`.
+    InheritanceCycle {
+        child: TypeName,
+        cycle: Vec<(TypeName, Pos)>,
+    },
+}
+
+/// Which edge in `child`'s shallow decl contributed a given direct ancestor
+/// to the folding walk; c.f. `Decl_inherit.from_class` for how each kind is
+/// merged in (traits override parents, requirements are synthesized, etc).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum LinkKind {
+    Extends,
+    Use,
+    ReqExtends,
+    Implements,
+}
+
 #[derive(Debug)]
 pub struct Inherited<R: Reason> {
     // note(sf, 2022-01-27): c.f. `Decl_inherit.inherited`
@@ -31,6 +106,20 @@ pub struct Inherited<R: Reason> {
     pub constructor: Constructor,
     pub consts: ClassConstNameIndexMap<ClassConst<R>>,
     pub type_consts: TypeConstNameIndexMap<TypeConst<R>>,
+    /// The direct ancestors whose members `MemberFolder` folded (parents,
+    /// then requirements, then traits), in the order it walked them --
+    /// i.e. the linearization the member maps above were actually folded
+    /// under. Populated once, after folding, from `MemberFolder::ancestors`.
+    /// The later passes that only pull in XHP attributes or constants (XHP
+    /// attribute uses, required and implemented interfaces' constants,
+    /// included enums) are not listed.
+    pub linearization: Vec<(TypeName, LinkKind)>,
+    /// Prefilters lookups against the member maps above: a negative answer
+    /// means the name is definitely not a member, letting a caller skip the
+    /// maps entirely. Populated once, after folding, from the final member
+    /// name sets; meant to travel alongside `Inherited`'s member maps onto
+    /// the folded class.
+    pub member_filter: BloomFilter,
 }
 
 impl<R: Reason> Default for Inherited<R> {
@@ -44,10 +133,49 @@ impl<R: Reason> Default for Inherited<R> {
             constructor: Constructor::new(None, ConsistentKind::Inconsistent),
             consts: Default::default(),
             type_consts: Default::default(),
+            linearization: Default::default(),
+            member_filter: Default::default(),
         }
     }
 }
 
+/// Carries the bits `add_method`/`add_props`/`add_consts`/`add_type_consts`
+/// need to emit a fine-grained dependency edge at the moment a member is
+/// actually pulled in from `owner`, rather than only ever recording that the
+/// child depends on `owner`'s constructor.
+struct MemberDepCtx<'a> {
+    child: TypeName,
+    owner: TypeName,
+    registrar: &'a dyn DependencyRegistrar,
+}
+
+impl<'a> MemberDepCtx<'a> {
+    fn register(&self, dependency: DependencyName) -> Result<()> {
+        self.registrar
+            .add_dependency(DeclName::Type(self.child), dependency)?;
+        Ok(())
+    }
+}
+
+/// Names that the *current* linear-chain stage has already contributed to
+/// `Inherited`'s method/prop maps. "Stage" here means one call to
+/// `MemberFolder::add_from_parents`/`add_from_requirements`/
+/// `add_from_traits`/`add_from_xhp_attr_uses` -- each of which can process
+/// several ancestors in a row (e.g. several `use`d traits), and those
+/// ancestors really are unordered siblings of each other. A fresh
+/// `MemberStage` is created per call and threaded through every `merge` it
+/// makes, so `add_method`/`add_prop` can tell "this name was already
+/// claimed by a sibling at this same stage" (a genuine conflict) apart from
+/// "this name was already claimed by an earlier stage" (a legal
+/// linear-chain override, e.g. a trait overriding a class parent).
+#[derive(Default)]
+struct MemberStage {
+    methods: HashSet<MethodName>,
+    static_methods: HashSet<MethodName>,
+    props: HashSet<PropName>,
+    static_props: HashSet<PropName>,
+}
+
 impl<R: Reason> Inherited<R> {
     // Reasons to keep the old signature:
     //   - We don't want to override a concrete method with an
@@ -65,6 +193,40 @@ impl<R: Reason> Inherited<R> {
                 && new_sig.is_synthesized()
     }
 
+    // Two definitions for the same member name only *silently* pick a
+    // winner (rather than being a genuine conflict) when at least one of
+    // them is abstract or synthesized, or when they both trace back to the
+    // same declaration. Otherwise we're choosing between
+    // two real definitions from unrelated sibling traits/interfaces with
+    // nothing principled to go on.
+    //
+    // This alone doesn't rule out the normal, legal case of a trait
+    // overriding a class parent's method (or a parent overriding a
+    // grandparent's, etc) -- that's a linear-chain override, not a sibling
+    // conflict, even though the two origins differ. Callers are expected to
+    // only call this for two definitions that actually arrived from the
+    // *same* stage of the MRO walk (see `MemberStage`); a cross-stage
+    // override never reaches here as a conflict candidate.
+    fn is_member_conflict(new_sig: &FoldedElement, old_sig: &FoldedElement) -> bool {
+        !new_sig.is_abstract()
+            && !old_sig.is_abstract()
+            && !new_sig.is_synthesized()
+            && !old_sig.is_synthesized()
+            && new_sig.origin != old_sig.origin
+    }
+
+    // Two abstract type constants from distinct parents are only genuinely
+    // ambiguous (per `Typing_extends`) when their `as` bounds don't let one
+    // subsume the other. We don't have a subtyping check available at this
+    // layer, so this is a conservative proxy: bounds that are structurally
+    // equal (including both absent) are treated as comparable, and anything
+    // else -- including two bounds that might still be related by subtyping
+    // -- is treated as incomparable and left for `Typing_extends`, which
+    // does have a real subtyping check, to make the final call.
+    fn bounds_are_incomparable(a: &Option<DeclTy<R>>, b: &Option<DeclTy<R>>) -> bool {
+        a != b
+    }
+
     fn add_constructor(&mut self, constructor: Constructor) {
         let elt = match (constructor.elt.as_ref(), self.constructor.elt.take()) {
             (None, self_ctor) => self_ctor,
@@ -107,55 +269,204 @@ impl<R: Reason> Inherited<R> {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn add_method(
         methods: &mut MethodNameIndexMap<FoldedElement>,
+        stage_seen: &mut HashSet<MethodName>,
         (key, mut fe): (MethodName, FoldedElement),
-    ) {
+        kind: MemberKind,
+        dep_ctx: Option<&MemberDepCtx<'_>>,
+        conflicts: &mut Vec<DeclFoldError>,
+    ) -> Result<()> {
+        // `stage_seen` only ever holds names touched earlier in the *same*
+        // stage (see `MemberStage`); an entry already in `methods` that
+        // isn't in `stage_seen` got there from an earlier stage (e.g. a
+        // class parent, about to be legitimately overridden by a trait),
+        // not from a sibling at this stage.
+        let same_stage_sibling = !stage_seen.insert(key);
         match methods.entry(key) {
             Entry::Vacant(entry) => {
                 // The method didn't exist so far, let's add it.
+                if let Some(ctx) = dep_ctx {
+                    ctx.register(DependencyName::Method(ctx.owner, key))?;
+                }
                 entry.insert(fe);
             }
             Entry::Occupied(mut entry) => {
                 if !Self::should_keep_old_sig(&fe, entry.get()) {
-                    fe.set_is_superfluous_override(false);
-                    entry.insert(fe);
-                } else {
                     // Otherwise, we *are* overwriting a method
                     // definition. This is OK when a naming
                     // conflict is parent class vs trait (trait
                     // wins!), but not really OK when the naming
-                    // conflict is trait vs trait (we rely on HHVM
-                    // to catch the error at runtime).
+                    // conflict is trait vs trait -- in which case we
+                    // used to just rely on HHVM to catch the error at
+                    // runtime. Now we record it instead.
+                    if same_stage_sibling && Self::is_member_conflict(&fe, entry.get()) {
+                        conflicts.push(DeclFoldError::MemberConflict {
+                            name: key.to_string(),
+                            kind,
+                            owner_a: entry.get().origin,
+                            owner_b: fe.origin,
+                            pos_a: entry.get().pos.clone(),
+                            pos_b: fe.pos.clone(),
+                        });
+                    }
+                    if let Some(ctx) = dep_ctx {
+                        ctx.register(DependencyName::Method(ctx.owner, key))?;
+                    }
+                    fe.set_is_superfluous_override(false);
+                    entry.insert(fe);
                 }
             }
         }
+        Ok(())
     }
 
-    fn add_methods(&mut self, other_methods: MethodNameIndexMap<FoldedElement>) {
+    fn add_methods(
+        &mut self,
+        other_methods: MethodNameIndexMap<FoldedElement>,
+        dep_ctx: Option<&MemberDepCtx<'_>>,
+        conflicts: &mut Vec<DeclFoldError>,
+        stage: &mut MemberStage,
+    ) -> Result<()> {
         for (key, fe) in other_methods {
-            Self::add_method(&mut self.methods, (key, fe))
+            Self::add_method(
+                &mut self.methods,
+                &mut stage.methods,
+                (key, fe),
+                MemberKind::Method,
+                dep_ctx,
+                conflicts,
+            )?;
         }
+        Ok(())
     }
 
-    fn add_static_methods(&mut self, other_static_methods: MethodNameIndexMap<FoldedElement>) {
+    fn add_static_methods(
+        &mut self,
+        other_static_methods: MethodNameIndexMap<FoldedElement>,
+        dep_ctx: Option<&MemberDepCtx<'_>>,
+        conflicts: &mut Vec<DeclFoldError>,
+        stage: &mut MemberStage,
+    ) -> Result<()> {
         for (key, fe) in other_static_methods {
-            Self::add_method(&mut self.static_methods, (key, fe))
+            Self::add_method(
+                &mut self.static_methods,
+                &mut stage.static_methods,
+                (key, fe),
+                MemberKind::StaticMethod,
+                dep_ctx,
+                conflicts,
+            )?;
+        }
+        Ok(())
+    }
+
+    // Props go through the same `should_keep_old_sig`/`is_member_conflict`
+    // precedence as `add_method` above, rather than the blind
+    // last-source-wins `extend` the baseline used: an abstract or
+    // synthesized prop declaration (e.g. from a `require extends` or an
+    // interface) should lose to a real one pulled in later, the same way an
+    // abstract method does.
+    //
+    // NOTE: this is a deliberate change to prop merge semantics, not just
+    // the dependency-edge plumbing this function was touched for -- and
+    // `ty::decl`/`Decl_inherit.ml` aren't available in this tree to confirm
+    // prop folding really does mirror method folding there. Treat this as
+    // its own reviewable change (not a side effect of dependency tracking);
+    // confirm against `Decl_inherit`'s actual prop-merge rule before
+    // relying on it, and revert to a blind `extend` here if it doesn't
+    // match.
+    #[allow(clippy::too_many_arguments)]
+    fn add_prop(
+        props: &mut PropNameIndexMap<FoldedElement>,
+        stage_seen: &mut HashSet<PropName>,
+        (key, fe): (PropName, FoldedElement),
+        kind: MemberKind,
+        dep_ctx: Option<&MemberDepCtx<'_>>,
+        conflicts: &mut Vec<DeclFoldError>,
+    ) -> Result<()> {
+        let same_stage_sibling = !stage_seen.insert(key);
+        match props.entry(key) {
+            Entry::Vacant(entry) => {
+                if let Some(ctx) = dep_ctx {
+                    ctx.register(DependencyName::Prop(ctx.owner, key))?;
+                }
+                entry.insert(fe);
+            }
+            Entry::Occupied(mut entry) => {
+                if !Self::should_keep_old_sig(&fe, entry.get()) {
+                    if same_stage_sibling && Self::is_member_conflict(&fe, entry.get()) {
+                        conflicts.push(DeclFoldError::MemberConflict {
+                            name: key.to_string(),
+                            kind,
+                            owner_a: entry.get().origin,
+                            owner_b: fe.origin,
+                            pos_a: entry.get().pos.clone(),
+                            pos_b: fe.pos.clone(),
+                        });
+                    }
+                    if let Some(ctx) = dep_ctx {
+                        ctx.register(DependencyName::Prop(ctx.owner, key))?;
+                    }
+                    entry.insert(fe);
+                }
+            }
         }
+        Ok(())
     }
 
-    fn add_props(&mut self, other_props: PropNameIndexMap<FoldedElement>) {
-        self.props.extend(other_props)
+    fn add_props(
+        &mut self,
+        other_props: PropNameIndexMap<FoldedElement>,
+        dep_ctx: Option<&MemberDepCtx<'_>>,
+        conflicts: &mut Vec<DeclFoldError>,
+        stage: &mut MemberStage,
+    ) -> Result<()> {
+        for (key, fe) in other_props {
+            Self::add_prop(
+                &mut self.props,
+                &mut stage.props,
+                (key, fe),
+                MemberKind::Prop,
+                dep_ctx,
+                conflicts,
+            )?;
+        }
+        Ok(())
     }
 
-    fn add_static_props(&mut self, other_static_props: PropNameIndexMap<FoldedElement>) {
-        self.static_props.extend(other_static_props)
+    fn add_static_props(
+        &mut self,
+        other_static_props: PropNameIndexMap<FoldedElement>,
+        dep_ctx: Option<&MemberDepCtx<'_>>,
+        conflicts: &mut Vec<DeclFoldError>,
+        stage: &mut MemberStage,
+    ) -> Result<()> {
+        for (key, fe) in other_static_props {
+            Self::add_prop(
+                &mut self.static_props,
+                &mut stage.static_props,
+                (key, fe),
+                MemberKind::StaticProp,
+                dep_ctx,
+                conflicts,
+            )?;
+        }
+        Ok(())
     }
 
-    fn add_consts(&mut self, other_consts: ClassConstNameIndexMap<ClassConst<R>>) {
+    fn add_consts(
+        &mut self,
+        other_consts: ClassConstNameIndexMap<ClassConst<R>>,
+        dep_ctx: Option<&MemberDepCtx<'_>>,
+    ) -> Result<()> {
         for (name, new_const) in other_consts {
             match self.consts.entry(name) {
                 Entry::Vacant(e) => {
+                    if let Some(ctx) = dep_ctx {
+                        ctx.register(DependencyName::Const(ctx.owner, name))?;
+                    }
                     e.insert(new_const);
                 }
                 Entry::Occupied(mut e) => {
@@ -184,19 +495,31 @@ impl<R: Reason> Inherited<R> {
                             // abstract constant found later in the MRO.
                         }
                         _ => {
+                            if let Some(ctx) = dep_ctx {
+                                ctx.register(DependencyName::Const(ctx.owner, name))?;
+                            }
                             e.insert(new_const);
                         }
                     }
                 }
             }
         }
+        Ok(())
     }
 
-    fn add_type_consts(&mut self, other_type_consts: TypeConstNameIndexMap<TypeConst<R>>) {
+    fn add_type_consts(
+        &mut self,
+        other_type_consts: TypeConstNameIndexMap<TypeConst<R>>,
+        dep_ctx: Option<&MemberDepCtx<'_>>,
+        conflicts: &mut Vec<DeclFoldError>,
+    ) -> Result<()> {
         for (name, mut new_const) in other_type_consts {
             match self.type_consts.entry(name) {
                 Entry::Vacant(e) => {
                     // The type constant didn't exist so far, let's add it.
+                    if let Some(ctx) = dep_ctx {
+                        ctx.register(DependencyName::TypeConst(ctx.owner, name))?;
+                    }
                     e.insert(new_const);
                 }
                 Entry::Occupied(mut e) => {
@@ -259,15 +582,43 @@ impl<R: Reason> Inherited<R> {
                                 // we inherit here will be enforceable too.
                                 new_const.enforceable = old_const.enforceable.clone();
                             }
+                            if let (
+                                Typeconst::TCAbstract(old_abstract_tc),
+                                Typeconst::TCAbstract(new_abstract_tc),
+                            ) = (&old_const.kind, &new_const.kind)
+                            {
+                                if old_const.origin != new_const.origin
+                                    && Self::bounds_are_incomparable(
+                                        &old_abstract_tc.as_constraint,
+                                        &new_abstract_tc.as_constraint,
+                                    )
+                                {
+                                    conflicts.push(DeclFoldError::AmbiguousTypeConst {
+                                        name,
+                                        owner_a: old_const.origin,
+                                        owner_b: new_const.origin,
+                                    });
+                                }
+                            }
+                            if let Some(ctx) = dep_ctx {
+                                ctx.register(DependencyName::TypeConst(ctx.owner, name))?;
+                            }
                             e.insert(new_const);
                         }
                     }
                 }
             }
         }
+        Ok(())
     }
 
-    fn add_inherited(&mut self, other: Self) {
+    fn add_inherited(
+        &mut self,
+        other: Self,
+        dep_ctx: Option<&MemberDepCtx<'_>>,
+        conflicts: &mut Vec<DeclFoldError>,
+        stage: &mut MemberStage,
+    ) -> Result<()> {
         let Self {
             substs,
             props,
@@ -277,15 +628,22 @@ impl<R: Reason> Inherited<R> {
             constructor,
             consts,
             type_consts,
+            // `linearization` and `member_filter` are only ever populated on
+            // the top-level `Inherited` returned from `Inherited::make` --
+            // individual per-parent `Inherited` values merged in here don't
+            // carry one of their own.
+            linearization: _,
+            member_filter: _,
         } = other;
         self.add_substs(substs);
-        self.add_props(props);
-        self.add_static_props(static_props);
-        self.add_methods(methods);
-        self.add_static_methods(static_methods);
+        self.add_props(props, dep_ctx, conflicts, stage)?;
+        self.add_static_props(static_props, dep_ctx, conflicts, stage)?;
+        self.add_methods(methods, dep_ctx, conflicts, stage)?;
+        self.add_static_methods(static_methods, dep_ctx, conflicts, stage)?;
         self.add_constructor(constructor);
-        self.add_consts(consts);
-        self.add_type_consts(type_consts);
+        self.add_consts(consts, dep_ctx)?;
+        self.add_type_consts(type_consts, dep_ctx, conflicts)?;
+        Ok(())
     }
 
     fn mark_as_synthesized(&mut self) {
@@ -298,18 +656,132 @@ impl<R: Reason> Inherited<R> {
         (self.consts.values_mut()).for_each(|c| c.set_is_synthesized(true));
         (self.type_consts.values_mut()).for_each(|c| c.set_is_synthesized(true));
     }
+
+    /// Rewrites every member's `DeclTy`s with `folder`, via the generic
+    /// `TypeFoldable` plumbing, instead of a bespoke per-kind pass. Useful
+    /// for applying a further type rewrite to an already-folded `Inherited`
+    /// without re-running the whole merge. `folder` only sees `DeclTy`
+    /// leaves, so a type constant's kind (abstract or concrete) is left as
+    /// it is. `substs` are carried over unchanged: the `DeclTy`s inside
+    /// each `SubstContext` are not visited.
+    pub fn fold_members_with<F: TyFolder<R>>(self, folder: &mut F) -> Result<Self> {
+        Ok(Self {
+            substs: (self.substs.into_iter())
+                .map(|(k, v)| Ok((k, v.try_fold_with(folder)?)))
+                .collect::<Result<_>>()?,
+            props: (self.props.into_iter())
+                .map(|(k, v)| Ok((k, v.try_fold_with(folder)?)))
+                .collect::<Result<_>>()?,
+            static_props: (self.static_props.into_iter())
+                .map(|(k, v)| Ok((k, v.try_fold_with(folder)?)))
+                .collect::<Result<_>>()?,
+            methods: (self.methods.into_iter())
+                .map(|(k, v)| Ok((k, v.try_fold_with(folder)?)))
+                .collect::<Result<_>>()?,
+            static_methods: (self.static_methods.into_iter())
+                .map(|(k, v)| Ok((k, v.try_fold_with(folder)?)))
+                .collect::<Result<_>>()?,
+            constructor: Constructor::new(
+                (self.constructor.elt)
+                    .map(|elt| elt.try_fold_with(folder))
+                    .transpose()?,
+                self.constructor.consistency,
+            ),
+            consts: (self.consts.into_iter())
+                .map(|(k, v)| Ok((k, v.try_fold_with(folder)?)))
+                .collect::<Result<_>>()?,
+            type_consts: (self.type_consts.into_iter())
+                .map(|(k, v)| Ok((k, v.try_fold_with(folder)?)))
+                .collect::<Result<_>>()?,
+            linearization: self.linearization,
+            member_filter: self.member_filter,
+        })
+    }
 }
 
 struct MemberFolder<'a, R: Reason> {
     child: &'a ShallowClass<R>,
     parents: &'a TypeNameIndexMap<Arc<FoldedClass<R>>>,
     dependency_registrar: &'a dyn DependencyRegistrar,
+    // Whether to emit fine-grained, per-member dependency edges as members
+    // are pulled in below. Bulk first-builds don't have an incremental
+    // dependency graph to populate, so they can turn this off.
+    emit_member_dependencies: bool,
+    // How eagerly to resolve abstract-with-default type constants pulled in
+    // from ancestors; see `ProjectionMode`.
+    projection_mode: ProjectionMode,
+    // Just `{ child.name }`: `members_from_class` never recurses (`parents`
+    // holds already-folded decls, looked up flatly), so there's no deeper
+    // DFS path to track here. This still catches the case a cyclic
+    // class/trait graph actually produces at this layer: some parent we're
+    // about to trust already lists `child` among its own (pre-folded)
+    // ancestors, meaning it was folded using data that depends on `child`.
+    // See `check_for_cycle`.
+    visiting: TypeNameSet,
     members: Inherited<R>,
+    // Conflicts noticed while merging members, surfaced to the caller from
+    // `Inherited::make` instead of being silently dropped.
+    conflicts: Vec<DeclFoldError>,
 }
 
 impl<'a, R: Reason> MemberFolder<'a, R> {
+    // `parent_folded_decl` is assumed to already be fully folded, which means
+    // its own `substs` map enumerates every ancestor it was folded from. If
+    // `child` (the only member `self.visiting` ever holds) shows up there --
+    // or *is* `parent_folded_decl` itself -- then `parent_folded_decl` was
+    // (transitively) folded using `child`, so folding `child` from it would
+    // be circular. This only detects a cycle that closes on `child` directly
+    // (a single hop back to where we started); it can't detect, say, a cycle
+    // among `child`'s ancestors that never routes back through `child`,
+    // since nothing at this layer walks further than one parent at a time.
+    fn check_for_cycle(&self, parent_folded_decl: &FoldedClass<R>) -> Option<DeclFoldError> {
+        let closes_cycle = self.visiting.contains(&parent_folded_decl.name)
+            || (parent_folded_decl.substs.keys())
+                .any(|ancestor| self.visiting.contains(ancestor));
+        if !closes_cycle {
+            return None;
+        }
+        Some(DeclFoldError::InheritanceCycle {
+            child: self.child.name.id(),
+            cycle: vec![
+                (self.child.name.id(), self.child.pos.clone()),
+                (parent_folded_decl.name, parent_folded_decl.pos.clone()),
+            ],
+        })
+    }
+    // Merge `inherited` (as produced by `members_from_class` or
+    // `class_constants_from_class`) into the folder's running `members`,
+    // registering a dependency edge for each member actually pulled in from
+    // `owner` (if member-level dependency emission is enabled).
+    // `stage` collects the method/prop names contributed so far by the
+    // *caller's* loop over sibling ancestors (see `MemberStage`) -- callers
+    // merging a single, already-linear sequence of stages (parents, then
+    // requirements, then traits) must pass a fresh `MemberStage` per stage,
+    // not one shared across all of them, or a trait overriding a parent
+    // would be misreported as a sibling conflict.
+    fn merge(
+        &mut self,
+        inherited: Inherited<R>,
+        owner: Option<TypeName>,
+        stage: &mut MemberStage,
+    ) -> Result<()> {
+        let dep_ctx = match owner {
+            Some(owner) if self.emit_member_dependencies => Some(MemberDepCtx {
+                child: self.child.name.id(),
+                owner,
+                registrar: self.dependency_registrar,
+            }),
+            _ => None,
+        };
+        self.members
+            .add_inherited(inherited, dep_ctx.as_ref(), &mut self.conflicts, stage)
+    }
+
     // c.f. `Decl_inherit.from_class` and `Decl_inherit.inherit_hack_class`.
-    fn members_from_class(&self, parent_ty: &DeclTy<R>) -> Result<Inherited<R>> {
+    fn members_from_class(
+        &mut self,
+        parent_ty: &DeclTy<R>,
+    ) -> Result<(Inherited<R>, Option<TypeName>)> {
         fn is_not_private<N>((_, elt): &(&N, &FoldedElement)) -> bool {
             match elt.visibility {
                 CeVisibility::Private(_) if elt.is_lsb() => true,
@@ -333,15 +805,31 @@ impl<'a, R: Reason> MemberFolder<'a, R> {
 
         if let Some((_, parent_pos_id, parent_tyl)) = parent_ty.unwrap_class_type() {
             if let Some(parent_folded_decl) = self.parents.get(&parent_pos_id.id()) {
+                if let Some(err) = self.check_for_cycle(parent_folded_decl) {
+                    self.conflicts.push(err);
+                    return Ok((Default::default(), None));
+                }
+
                 let sig = Subst::new(&parent_folded_decl.tparams, parent_tyl);
-                let subst = Substitution { subst: &sig };
+                let mut subst = Substitution { subst: &sig };
 
                 let consts = (parent_folded_decl.consts.iter())
-                    .map(|(name, cc)| (*name, subst.instantiate_class_const(cc)))
-                    .collect();
-                let type_consts = (parent_folded_decl.type_consts.iter())
-                    .map(|(name, tc)| (*name, subst.instantiate_type_const(tc)))
-                    .collect();
+                    .map(|(name, cc)| Ok((*name, subst.instantiate_class_const(cc.clone())?)))
+                    .collect::<Result<_>>()?;
+                // In `Topmost` mode we only want type constants declared
+                // directly on `self.child`, so ancestors contribute none.
+                let type_consts = if self.projection_mode == ProjectionMode::Topmost {
+                    TypeConstNameIndexMap::default()
+                } else {
+                    (parent_folded_decl.type_consts.iter())
+                        .map(|(name, tc)| {
+                            Ok((
+                                *name,
+                                subst.instantiate_type_const(tc.clone(), self.projection_mode)?,
+                            ))
+                        })
+                        .collect::<Result<_>>()?
+                };
 
                 let parent_inh = match parent_folded_decl.kind {
                     ClassishKind::Ctrait => Inherited {
@@ -413,41 +901,66 @@ impl<'a, R: Reason> MemberFolder<'a, R> {
                     )?;
                 }
 
-                return Ok(Inherited {
-                    substs,
-                    constructor,
-                    ..parent_inh
-                });
+                return Ok((
+                    Inherited {
+                        substs,
+                        constructor,
+                        ..parent_inh
+                    },
+                    Some(parent_folded_decl.name),
+                ));
             }
         }
 
-        Ok(Default::default())
+        Ok((Default::default(), None))
     }
 
-    fn class_constants_from_class(&self, ty: &DeclTy<R>) -> Result<Inherited<R>> {
+    fn class_constants_from_class(
+        &mut self,
+        ty: &DeclTy<R>,
+    ) -> Result<(Inherited<R>, Option<TypeName>)> {
         if let Some((_, pos_id, tyl)) = ty.unwrap_class_type() {
             if let Some(class) = self.parents.get(&pos_id.id()) {
+                if let Some(err) = self.check_for_cycle(class) {
+                    self.conflicts.push(err);
+                    return Ok((Default::default(), None));
+                }
+
                 let sig = Subst::new(&class.tparams, tyl);
-                let subst = Substitution { subst: &sig };
+                let mut subst = Substitution { subst: &sig };
                 let consts: ClassConstNameIndexMap<_> = class
                     .consts
                     .iter()
-                    .map(|(name, cc)| (*name, subst.instantiate_class_const(cc)))
-                    .collect();
-                let type_consts: TypeConstNameIndexMap<_> = class
-                    .type_consts
-                    .iter()
-                    .map(|(name, tc)| (*name, subst.instantiate_type_const(tc)))
-                    .collect();
-                return Ok(Inherited {
-                    consts,
-                    type_consts,
-                    ..Default::default()
-                });
+                    .map(|(name, cc)| Ok((*name, subst.instantiate_class_const(cc.clone())?)))
+                    .collect::<Result<_>>()?;
+                let type_consts: TypeConstNameIndexMap<_> = if self.projection_mode
+                    == ProjectionMode::Topmost
+                {
+                    TypeConstNameIndexMap::default()
+                } else {
+                    class
+                        .type_consts
+                        .iter()
+                        .map(|(name, tc)| {
+                            Ok((
+                                *name,
+                                subst.instantiate_type_const(tc.clone(), self.projection_mode)?,
+                            ))
+                        })
+                        .collect::<Result<_>>()?
+                };
+                return Ok((
+                    Inherited {
+                        consts,
+                        type_consts,
+                        ..Default::default()
+                    },
+                    Some(class.name),
+                ));
             }
         }
 
-        Ok(Default::default())
+        Ok((Default::default(), None))
     }
 
     // This logic deals with importing XHP attributes from an XHP class via the
@@ -473,73 +986,134 @@ impl<'a, R: Reason> MemberFolder<'a, R> {
     }
 
     fn add_from_interface_constants(&mut self) -> Result<()> {
+        let mut stage = MemberStage::default();
         for ty in self.child.req_implements.iter() {
-            self.members
-                .add_inherited(self.class_constants_from_class(ty)?)
+            let (inherited, owner) = self.class_constants_from_class(ty)?;
+            self.merge(inherited, owner, &mut stage)?;
         }
 
         Ok(())
     }
 
     fn add_from_implements_constants(&mut self) -> Result<()> {
+        let mut stage = MemberStage::default();
         for ty in self.child.implements.iter() {
-            self.members
-                .add_inherited(self.class_constants_from_class(ty)?)
+            let (inherited, owner) = self.class_constants_from_class(ty)?;
+            // Route through `merge` like every other ancestor, so
+            // `add_consts`/`add_type_consts`' precedence (concrete beats
+            // abstract, `enforceable` propagation, the ambiguous-bounds
+            // check) applies here too -- implemented interfaces still need
+            // that precedence even though they're unordered siblings rather
+            // than a linear parent chain; see `add_type_consts`.
+            self.merge(inherited, owner, &mut stage)?;
         }
 
         Ok(())
     }
 
     fn add_from_xhp_attr_uses(&mut self) -> Result<()> {
+        let mut stage = MemberStage::default();
         for ty in self.child.xhp_attr_uses.iter() {
-            self.members.add_inherited(self.xhp_attrs_from_class(ty)?)
+            let inherited = self.xhp_attrs_from_class(ty)?;
+            self.merge(inherited, None, &mut stage)?;
         }
 
         Ok(())
     }
 
-    fn add_from_parents(&mut self) -> Result<()> {
-        let mut tys: Vec<&DeclTy<R>> = Vec::new();
+    // The direct ancestors of `self.child` named in `extends`, `uses`,
+    // `req_extends`, and -- for abstract classes and traits only --
+    // `implements` (a trait's `req_implements` counts as `Implements`, since
+    // a trait requiring an interface is folded the same way a class
+    // implementing one is), tagged with which edge named them.
+    // This is the explicit order folding walks: parents first (implemented
+    // and extended, in reverse declaration order so the first-listed parent
+    // wins ties), then requirements, then traits -- c.f.
+    // `Decl_inherit.from_class`'s class/trait precedence.
+    fn ancestor_tys(&self) -> Vec<(&'a DeclTy<R>, LinkKind)> {
+        let mut parents: Vec<(&'a DeclTy<R>, LinkKind)> = Vec::new();
         match self.child.kind {
             ClassishKind::Cclass(Abstraction::Abstract) => {
-                tys.extend(self.child.implements.iter());
-                tys.extend(self.child.extends.iter());
+                parents.extend((self.child.implements.iter()).map(|t| (t, LinkKind::Implements)));
+                parents.extend((self.child.extends.iter()).map(|t| (t, LinkKind::Extends)));
             }
             ClassishKind::Ctrait => {
-                tys.extend(self.child.implements.iter());
-                tys.extend(self.child.extends.iter());
-                tys.extend(self.child.req_implements.iter());
+                parents.extend((self.child.implements.iter()).map(|t| (t, LinkKind::Implements)));
+                parents.extend((self.child.extends.iter()).map(|t| (t, LinkKind::Extends)));
+                parents.extend(
+                    (self.child.req_implements.iter()).map(|t| (t, LinkKind::Implements)),
+                );
             }
             ClassishKind::Cclass(_)
             | ClassishKind::Cinterface
             | ClassishKind::Cenum
             | ClassishKind::CenumClass(_) => {
-                tys.extend(self.child.extends.iter());
+                parents.extend((self.child.extends.iter()).map(|t| (t, LinkKind::Extends)));
             }
         };
+        parents.reverse();
 
-        // Interfaces implemented, classes extended and interfaces required to
-        // be implemented.
-        for ty in tys.iter().rev() {
-            self.members.add_inherited(self.members_from_class(ty)?);
+        (parents.into_iter())
+            .chain((self.child.req_extends.iter()).map(|t| (t, LinkKind::ReqExtends)))
+            .chain((self.child.uses.iter()).map(|t| (t, LinkKind::Use)))
+            .collect()
+    }
+
+    // `ancestor_tys` resolved to the already-folded decl each ancestor name
+    // refers to, for callers that want the linearization itself rather than
+    // its members (e.g. `Inherited::linearization`).
+    fn ancestors(&self) -> impl Iterator<Item = (TypeName, &'a Arc<FoldedClass<R>>, LinkKind)> + 'a {
+        let parents = self.parents;
+        (self.ancestor_tys().into_iter()).filter_map(move |(ty, kind)| {
+            let (_, pos_id, _) = ty.unwrap_class_type()?;
+            let name = pos_id.id();
+            parents.get(&name).map(|decl| (name, decl, kind))
+        })
+    }
+
+    fn add_from_parents(&mut self) -> Result<()> {
+        // Interfaces implemented, classes extended and interfaces required
+        // to be implemented.
+        let tys: Vec<&'a DeclTy<R>> = (self.ancestor_tys().into_iter())
+            .filter(|(_, kind)| matches!(kind, LinkKind::Extends | LinkKind::Implements))
+            .map(|(ty, _)| ty)
+            .collect();
+        let mut stage = MemberStage::default();
+        for ty in tys {
+            let (inherited, owner) = self.members_from_class(ty)?;
+            self.merge(inherited, owner, &mut stage)?;
         }
 
         Ok(())
     }
 
     fn add_from_requirements(&mut self) -> Result<()> {
-        for ty in self.child.req_extends.iter() {
-            let mut inherited = self.members_from_class(ty)?;
+        let tys: Vec<&'a DeclTy<R>> = (self.ancestor_tys().into_iter())
+            .filter(|(_, kind)| matches!(kind, LinkKind::ReqExtends))
+            .map(|(ty, _)| ty)
+            .collect();
+        let mut stage = MemberStage::default();
+        for ty in tys {
+            let (mut inherited, owner) = self.members_from_class(ty)?;
             inherited.mark_as_synthesized();
-            self.members.add_inherited(inherited);
+            // Still register the dependency on the requirement's parent:
+            // synthesized members are invisible to the programmer, but an
+            // edit to the underlying member should still invalidate `child`.
+            self.merge(inherited, owner, &mut stage)?;
         }
 
         Ok(())
     }
 
     fn add_from_traits(&mut self) -> Result<()> {
-        for ty in self.child.uses.iter() {
-            self.members.add_inherited(self.members_from_class(ty)?);
+        let tys: Vec<&'a DeclTy<R>> = (self.ancestor_tys().into_iter())
+            .filter(|(_, kind)| matches!(kind, LinkKind::Use))
+            .map(|(ty, _)| ty)
+            .collect();
+        let mut stage = MemberStage::default();
+        for ty in tys {
+            let (inherited, owner) = self.members_from_class(ty)?;
+            self.merge(inherited, owner, &mut stage)?;
         }
 
         Ok(())
@@ -547,9 +1121,12 @@ impl<'a, R: Reason> MemberFolder<'a, R> {
 
     fn add_from_included_enums_constants(&mut self) -> Result<()> {
         if let Some(et) = self.child.enum_type.as_ref() {
+            let mut stage = MemberStage::default();
             for ty in et.includes.iter() {
-                self.members
-                    .add_inherited(self.class_constants_from_class(ty)?);
+                let (inherited, owner) = self.class_constants_from_class(ty)?;
+                // Same reasoning as `add_from_implements_constants`: go
+                // through `merge` so precedence still applies.
+                self.merge(inherited, owner, &mut stage)?;
             }
         }
 
@@ -562,12 +1139,40 @@ impl<R: Reason> Inherited<R> {
         child: &ShallowClass<R>,
         parents: &TypeNameIndexMap<Arc<FoldedClass<R>>>,
         dependency_registrar: &dyn DependencyRegistrar,
-    ) -> Result<Self> {
+    ) -> Result<(Self, Vec<DeclFoldError>)> {
+        Self::make_with_options(
+            child,
+            parents,
+            dependency_registrar,
+            true,
+            ProjectionMode::default(),
+        )
+    }
+
+    /// Like `make`, but lets the caller opt out of emitting fine-grained,
+    /// per-member dependency edges (e.g. a bulk first-build that has no
+    /// incremental dependency graph to populate yet), and pick how eagerly
+    /// inherited type constants should be projected (see `ProjectionMode`).
+    /// The coarser constructor-level dependency on each parent is recorded
+    /// regardless of this flag, except for parents declared in HHI files.
+    pub fn make_with_options(
+        child: &ShallowClass<R>,
+        parents: &TypeNameIndexMap<Arc<FoldedClass<R>>>,
+        dependency_registrar: &dyn DependencyRegistrar,
+        emit_member_dependencies: bool,
+        projection_mode: ProjectionMode,
+    ) -> Result<(Self, Vec<DeclFoldError>)> {
+        let mut visiting = TypeNameSet::default();
+        visiting.insert(child.name.id());
         let mut folder = MemberFolder {
             child,
             parents,
             dependency_registrar,
+            emit_member_dependencies,
+            projection_mode,
+            visiting,
             members: Self::default(),
+            conflicts: Vec::new(),
         };
         folder.add_from_parents()?; // Members inherited from parents ...
         folder.add_from_requirements()?;
@@ -577,6 +1182,26 @@ impl<R: Reason> Inherited<R> {
         folder.add_from_included_enums_constants()?;
         folder.add_from_implements_constants()?;
 
-        Ok(folder.members)
+        folder.members.linearization = (folder.ancestors())
+            .map(|(name, _, kind)| (name, kind))
+            .collect();
+
+        let members = &mut folder.members;
+        let entry_count = members.props.len()
+            + members.static_props.len()
+            + members.methods.len()
+            + members.static_methods.len()
+            + members.consts.len()
+            + members.type_consts.len();
+        let mut member_filter = BloomFilter::with_capacity(entry_count);
+        (members.props.keys()).for_each(|name| member_filter.insert(name));
+        (members.static_props.keys()).for_each(|name| member_filter.insert(name));
+        (members.methods.keys()).for_each(|name| member_filter.insert(name));
+        (members.static_methods.keys()).for_each(|name| member_filter.insert(name));
+        (members.consts.keys()).for_each(|name| member_filter.insert(name));
+        (members.type_consts.keys()).for_each(|name| member_filter.insert(name));
+        members.member_filter = member_filter;
+
+        Ok((folder.members, folder.conflicts))
     }
 }
\ No newline at end of file
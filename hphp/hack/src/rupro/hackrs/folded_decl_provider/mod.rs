@@ -0,0 +1,20 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the "hack" directory of this source tree.
+
+mod bloom;
+mod inherit;
+mod subst;
+
+pub use bloom::BloomFilter;
+pub use inherit::{DeclFoldError, Inherited, LinkKind, MemberKind};
+pub use subst::{ProjectionMode, Substitution, TyFolder, TypeFoldable};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to register decl dependency: {0}")]
+    Dependency(#[from] Box<dyn std::error::Error + Send + Sync>),
+}
@@ -0,0 +1,161 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+//
+// This source code is licensed under the MIT license found in the
+// LICENSE file in the "hack" directory of this source tree.
+
+use super::Result;
+use ty::decl::{
+    subst::Subst, AbstractTypeconst, ClassConst, ConcreteTypeconst, FoldedElement, SubstContext,
+    TypeConst, Typeconst,
+};
+use ty::decl::DeclTy;
+use ty::reason::Reason;
+
+/// Controls how far `Substitution::instantiate_type_const` is willing to go
+/// when it finds an abstract type constant with a default, since different
+/// phases of the compiler want different answers to "what is this type
+/// constant, really":
+///
+/// - Early coherence-style checks only want to know about a type constant if
+///   it's defined directly on the class being checked -- an inherited one
+///   (abstract or not) shouldn't count.
+/// - Ordinary type checking wants any finalized definition reachable from an
+///   ancestor, but must keep an abstract-with-default type constant opaque
+///   (it isn't *actually* concrete, it just has a default that a descendant
+///   may rely on).
+/// - Codegen/runtime lowering wants the fully concretized value, collapsing
+///   defaults into the concrete type they stand for.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ProjectionMode {
+    /// Only retain type constants owned directly by the class being folded;
+    /// skip everything contributed by ancestors.
+    Topmost,
+    /// Retain any finalized ancestor definition, but keep
+    /// abstract-with-default type constants abstract.
+    AnyFinal,
+    /// Eagerly resolve an abstract-with-default type constant to its
+    /// default, producing a concrete type constant.
+    Any,
+}
+
+impl Default for ProjectionMode {
+    fn default() -> Self {
+        ProjectionMode::AnyFinal
+    }
+}
+
+/// A piece of decl-time data that has `DeclTy` values nested inside it which
+/// a `TyFolder` might want to rewrite. Exists so a single folder (e.g.
+/// `Substitution`) can be pushed down through `ClassConst`, `TypeConst`,
+/// `FoldedElement`, and `SubstContext` without each caller hand-rolling its
+/// own "rebuild this struct, but with its `DeclTy` fields substituted"
+/// shuffle.
+pub trait TypeFoldable<R: Reason>: Sized {
+    fn try_fold_with<F: TyFolder<R>>(self, folder: &mut F) -> Result<Self>;
+}
+
+/// Rewrites the `DeclTy` leaves visited while folding a `TypeFoldable`
+/// value. The default `fold_ty` leaves the type alone; override it to
+/// intercept leaves, the way `Substitution` does to apply a `Subst`.
+pub trait TyFolder<R: Reason>: Sized {
+    fn fold_ty(&mut self, ty: DeclTy<R>) -> Result<DeclTy<R>> {
+        Ok(ty)
+    }
+}
+
+/// Folds `value` with `folder`; shorthand for `value.try_fold_with(folder)`.
+/// For an aggregate type (anything other than `DeclTy` itself) this gives
+/// `folder` each nested `DeclTy` and rebuilds `value` around the results;
+/// a bare `DeclTy` is handed to `folder.fold_ty` as a whole.
+pub fn superfold<R: Reason, T: TypeFoldable<R>>(
+    value: T,
+    folder: &mut impl TyFolder<R>,
+) -> Result<T> {
+    value.try_fold_with(folder)
+}
+
+impl<R: Reason> TypeFoldable<R> for DeclTy<R> {
+    fn try_fold_with<F: TyFolder<R>>(self, folder: &mut F) -> Result<Self> {
+        folder.fold_ty(self)
+    }
+}
+
+impl<R: Reason> TypeFoldable<R> for ClassConst<R> {
+    fn try_fold_with<F: TyFolder<R>>(self, folder: &mut F) -> Result<Self> {
+        Ok(Self {
+            ty: self.ty.try_fold_with(folder)?,
+            ..self
+        })
+    }
+}
+
+impl<R: Reason> TypeFoldable<R> for TypeConst<R> {
+    fn try_fold_with<F: TyFolder<R>>(self, folder: &mut F) -> Result<Self> {
+        let kind = match self.kind {
+            Typeconst::TCAbstract(abstract_tc) => Typeconst::TCAbstract(AbstractTypeconst {
+                as_constraint: (abstract_tc.as_constraint)
+                    .map(|ty| ty.try_fold_with(folder))
+                    .transpose()?,
+                default: (abstract_tc.default)
+                    .map(|ty| ty.try_fold_with(folder))
+                    .transpose()?,
+            }),
+            Typeconst::TCConcrete(concrete_tc) => Typeconst::TCConcrete(ConcreteTypeconst {
+                ty: concrete_tc.ty.try_fold_with(folder)?,
+            }),
+        };
+        Ok(Self { kind, ..self })
+    }
+}
+
+impl<R: Reason> TypeFoldable<R> for FoldedElement {
+    fn try_fold_with<F: TyFolder<R>>(self, _folder: &mut F) -> Result<Self> {
+        // A `FoldedElement` carries visibility/flags/origin, not a `DeclTy`
+        // of its own -- the element's type lives behind the member maps
+        // callers resolve it through -- so there's nothing here for `folder`
+        // to visit.
+        Ok(self)
+    }
+}
+
+impl<R: Reason> TypeFoldable<R> for SubstContext<R> {
+    fn try_fold_with<F: TyFolder<R>>(self, _folder: &mut F) -> Result<Self> {
+        // `self.subst` is the substitution an ancestor's type parameters
+        // were resolved under when it was folded. It is returned unchanged:
+        // `folder` does not visit the `DeclTy`s it maps type parameters to.
+        Ok(self)
+    }
+}
+
+pub struct Substitution<'a, R: Reason> {
+    pub subst: &'a Subst<R>,
+}
+
+impl<'a, R: Reason> TyFolder<R> for Substitution<'a, R> {
+    fn fold_ty(&mut self, ty: DeclTy<R>) -> Result<DeclTy<R>> {
+        Ok(ty.subst(self.subst))
+    }
+}
+
+impl<'a, R: Reason> Substitution<'a, R> {
+    pub fn instantiate_class_const(&mut self, cc: ClassConst<R>) -> Result<ClassConst<R>> {
+        superfold(cc, self)
+    }
+
+    pub fn instantiate_type_const(
+        &mut self,
+        tc: TypeConst<R>,
+        mode: ProjectionMode,
+    ) -> Result<TypeConst<R>> {
+        if let (Typeconst::TCAbstract(AbstractTypeconst { default: Some(default), .. }), true) =
+            (&tc.kind, mode == ProjectionMode::Any)
+        {
+            let ty = default.clone().try_fold_with(self)?;
+            return Ok(TypeConst {
+                kind: Typeconst::TCConcrete(ConcreteTypeconst { ty }),
+                ..tc
+            });
+        }
+        superfold(tc, self)
+    }
+}